@@ -1,16 +1,17 @@
-use hyper::body::to_bytes;
-use hyper::Method;
-use serde::Deserialize;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value}; // JSON macro and type live here
 use std::env;
 use std::string::String;
-use vercel_runtime::{run, service_fn, Error, Request};
+use std::sync::OnceLock;
+use std::time::Duration;
+use vercel_runtime::{run, Body, Error, Request, Response, StatusCode};
 
 #[path = "../src/movie.rs"]
 mod movie;
 use movie::Movie;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 struct MovieInput {
     title: String,
     tagline: Option<String>,
@@ -18,7 +19,7 @@ struct MovieInput {
     release_date: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 struct MovieUpdate {
     title: Option<String>,
     tagline: Option<String>,
@@ -26,211 +27,768 @@ struct MovieUpdate {
     release_date: Option<String>,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Error> {
-    // Official Vercel v2 initialization
-    run(service_fn(handler)).await
+/// One update entry in a batch request: the target `id` plus the fields to
+/// change, spliced together so clients send a flat object.
+#[derive(Debug, Deserialize)]
+struct BatchUpdate {
+    id: String,
+    #[serde(flatten)]
+    fields: MovieUpdate,
 }
 
-pub async fn handler(req: Request) -> Result<Value, Error> {
-    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
-    let supabase_key = env::var("SUPABASE_ANON_KEY").unwrap_or_default();
+/// Payload for the `POST /?batch=1` bulk endpoint. Each group is optional so a
+/// client can send only the operations it needs.
+#[derive(Debug, Deserialize)]
+struct BatchRequest {
+    #[serde(default)]
+    insert: Vec<MovieInput>,
+    #[serde(default)]
+    update: Vec<BatchUpdate>,
+    #[serde(default)]
+    delete: Vec<String>,
+}
+
+/// Everything that can go wrong while serving a request, mapped to the HTTP
+/// status the client should actually see instead of a blanket 200.
+#[derive(Debug)]
+enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    #[allow(dead_code)] // part of the error taxonomy; reserved for id lookups
+    NotFound(String),
+    /// A server-side problem unrelated to the upstream, e.g. missing config.
+    Internal(String),
+    /// A call to Supabase (or a network error reaching it) failed; the wrapped
+    /// status is the upstream one (502 for a network error), and the client
+    /// always sees a 502.
+    Upstream(StatusCode, String),
+    Json(serde_json::Error),
+}
 
-/******** DEBUG code to check variables ********/
-    println!("ENV CHECK - URL length: {}, Key length: {}", supabase_url.len(), supabase_key.len());
+impl From<serde_json::Error> for ApiError {
+    fn from(err: serde_json::Error) -> Self {
+        ApiError::Json(err)
+    }
+}
 
-    if supabase_url.is_empty() || supabase_key.is_empty() {
-        return Ok(json!({
-            "error": "Backend environment variables are not set",
-            "details": "Check Vercel Dashboard > Settings > Environment Variables"
-        }));
+impl From<reqwest::Error> for ApiError {
+    fn from(err: reqwest::Error) -> Self {
+        ApiError::Upstream(StatusCode::BAD_GATEWAY, err.to_string())
     }
+}
 
-    // This will show up in Vercel 'Logs' but won't reveal your secret
-    if supabase_key.is_empty() {
-        eprintln!("CRITICAL: SUPABASE_ANON_KEY is empty on the server!");
-    } else {
-        println!("SUCCESS: SUPABASE_ANON_KEY detected (Length: {})", supabase_key.len());
+impl ApiError {
+    /// The status code we return to the caller for this error.
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::Json(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Upstream(..) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    /// Render the error as a consistent `{ "error", "details" }` JSON body.
+    fn into_response(self) -> Response<Body> {
+        let status = self.status_code();
+        let (error, details) = match self {
+            ApiError::BadRequest(msg) => ("Bad request".to_string(), msg),
+            ApiError::Unauthorized(msg) => ("Unauthorized".to_string(), msg),
+            ApiError::NotFound(msg) => ("Not found".to_string(), msg),
+            ApiError::Internal(msg) => ("Internal server error".to_string(), msg),
+            ApiError::Upstream(code, body) => (format!("Upstream returned {}", code.as_u16()), body),
+            ApiError::Json(err) => ("Invalid JSON payload".to_string(), err.to_string()),
+        };
+        json_response(status, json!({ "error": error, "details": details }))
+    }
+}
+
+/// A process-wide reqwest client, built once and reused across warm
+/// invocations so we pay connection-pool setup only on a cold start.
+///
+/// The TLS backend is selected at build time through the crate's cargo
+/// features (`default-tls`, `rustls-tls-native-roots`,
+/// `rustls-tls-webpki-roots`), mirroring rustypipe, so the binary can be built
+/// against rustls where OpenSSL is unavailable.
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new())
+    })
+}
+
+/// Send a request, retrying transient failures (connection errors and
+/// `429`/`5xx` responses) up to `MAX_RETRIES` times with jittered exponential
+/// backoff. A `Retry-After` header, when present, overrides the computed delay.
+async fn send_with_retry(
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let max_retries: u32 = env::var("MAX_RETRIES").ok().and_then(|v| v.parse().ok()).unwrap_or(3);
+
+    let mut attempt: u32 = 0;
+    loop {
+        // Clone so the builder survives a retry; a non-cloneable (streaming)
+        // body can only be sent once, so fall back to a single attempt.
+        let Some(attempt_req) = request.try_clone() else {
+            return request.send().await;
+        };
+        let result = attempt_req.send().await;
+
+        let retryable = match &result {
+            Err(err) => err.is_connect() || err.is_timeout() || err.is_request(),
+            Ok(res) => {
+                let status = res.status();
+                status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+        };
+        if !retryable || attempt >= max_retries {
+            return result;
+        }
+
+        let delay = result
+            .as_ref()
+            .ok()
+            .and_then(retry_after_delay)
+            .unwrap_or_else(|| backoff_delay(attempt));
+        tokio::time::sleep(delay).await;
+        attempt += 1;
     }
+}
+
+/// Parse a `Retry-After` header expressed in whole seconds.
+fn retry_after_delay(res: &reqwest::Response) -> Option<Duration> {
+    let seconds: u64 = res.headers().get("retry-after")?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
 
+/// Exponential backoff (100ms, 200ms, 400ms, ...) with a small jitter so
+/// concurrent retries don't thunder against the backend in lock-step.
+fn backoff_delay(attempt: u32) -> Duration {
+    let base_ms = 100u64.saturating_mul(1u64 << attempt.min(10));
+    Duration::from_millis(base_ms + jitter_ms())
+}
+
+/// Cheap sub-100ms jitter derived from the wall clock, avoiding an extra rng
+/// dependency.
+fn jitter_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| (d.subsec_nanos() % 100) as u64)
+        .unwrap_or(0)
+}
 
-    // Query parsing
-    let uri_string = req.uri().to_string();
-    let query_parts: std::collections::HashMap<String, String> = uri_string
-        .split('?')
+/// Split a request URI's query string into key/value pairs. Values are kept
+/// whole (`splitn`) so an `=`-bearing value survives; cursors use an unpadded
+/// URL-safe alphabet, so they need no percent-decoding.
+fn parse_query(uri: &str) -> std::collections::HashMap<String, String> {
+    uri.split('?')
         .nth(1)
         .unwrap_or("")
         .split('&')
         .filter(|s| !s.is_empty())
         .map(|s| {
-            let mut parts = s.split('=');
+            let mut parts = s.splitn(2, '=');
             (
                 parts.next().unwrap_or("").to_string(),
                 parts.next().unwrap_or("").to_string(),
             )
         })
-        .collect();
+        .collect()
+}
+
+/// Decode an opaque `cursor` query param into the `(release_date, id)` tuple of
+/// the last row a client has already seen. An empty date component marks a row
+/// whose `release_date` is null (the nulls-last tail). Returns `None` for a
+/// malformed cursor so the caller can treat it as a fresh (first page) request.
+fn decode_cursor(cursor: &str) -> Option<(String, String)> {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    let raw = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    let decoded = String::from_utf8(raw).ok()?;
+    let (date, id) = decoded.split_once('|')?;
+    Some((date.to_string(), id.to_string()))
+}
+
+/// Encode the `(release_date, id)` of a row into an opaque forward cursor. A URL
+/// safe, unpadded alphabet keeps the value intact through query-string parsing.
+fn encode_cursor(release_date: &str, id: &str) -> String {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    URL_SAFE_NO_PAD.encode(format!("{}|{}", release_date, id))
+}
+
+/// Build the PostgREST `or=(...)` predicate that selects rows strictly after
+/// the `(release_date, id)` cursor under
+/// `order=release_date.desc.nullslast,id.desc`.
+///
+/// An empty `release_date` means the cursor points into the null-date tail, so
+/// we continue by id among the remaining null-date rows. Otherwise we want
+/// earlier dates, the same date with an earlier id, and — since nulls sort last
+/// — every null-date row.
+fn keyset_predicate(release_date: &str, id: &str) -> String {
+    if release_date.is_empty() {
+        format!("(and(release_date.is.null,id.lt.{id}))", id = id)
+    } else {
+        format!(
+            "(release_date.lt.{date},and(release_date.eq.{date},id.lt.{id}),release_date.is.null)",
+            date = release_date,
+            id = id,
+        )
+    }
+}
+
+/// Pull the cursor components out of an already-serialized movie row. A null
+/// `release_date` is encoded as an empty date so a full page still yields a
+/// cursor instead of being mistaken for the end of the list.
+fn cursor_fields(movie: &Movie) -> Option<(String, String)> {
+    let value = serde_json::to_value(movie).ok()?;
+    let date = value
+        .get("release_date")
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_string();
+    let id = match value.get("id")? {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    Some((date, id))
+}
+
+/// Serialize `value` into a JSON response with the given status.
+fn json_response(status: StatusCode, value: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::Text(value.to_string()))
+        .expect("response builder with static header is infallible")
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Official Vercel v2 initialization
+    run(handler).await
+}
+
+pub async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    let allowed_origin = env::var("ALLOWED_ORIGIN").unwrap_or_else(|_| "*".to_string());
+
+    // Answer CORS preflight before touching the backend so a browser front-end
+    // can negotiate the actual request.
+    if req.method() == Method::OPTIONS {
+        return Ok(preflight_response(&allowed_origin));
+    }
+
+    let mut response = match dispatch(req).await {
+        Ok(response) => response,
+        Err(err) => err.into_response(),
+    };
+    apply_cors_and_security(response.headers_mut(), &allowed_origin);
+    Ok(response)
+}
+
+/// Build the 204 response for an `OPTIONS` preflight request.
+fn preflight_response(allowed_origin: &str) -> Response<Body> {
+    let mut response = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::Empty)
+        .expect("preflight response is infallible");
+    let headers = response.headers_mut();
+    headers.insert(
+        "Access-Control-Allow-Methods",
+        "GET, POST, PATCH, DELETE, OPTIONS".parse().unwrap(),
+    );
+    headers.insert(
+        "Access-Control-Allow-Headers",
+        "Authorization, Content-Type, apikey".parse().unwrap(),
+    );
+    apply_cors_and_security(headers, allowed_origin);
+    response
+}
+
+/// Attach CORS and hardening headers to every real response.
+fn apply_cors_and_security(headers: &mut reqwest::header::HeaderMap, allowed_origin: &str) {
+    if let Ok(origin) = allowed_origin.parse() {
+        headers.insert("Access-Control-Allow-Origin", origin);
+    }
+    headers.insert("X-Content-Type-Options", "nosniff".parse().unwrap());
+    headers.insert("Cache-Control", "no-store".parse().unwrap());
+}
+
+/// Gate mutating methods behind a bearer token. `GET` stays public; any other
+/// method requires an `Authorization: Bearer <token>` header matching the
+/// `API_WRITE_TOKEN` env var, otherwise a 401 is returned.
+fn authorize(req: &Request) -> Result<(), ApiError> {
+    if req.method() == Method::GET {
+        return Ok(());
+    }
+
+    let expected = env::var("API_WRITE_TOKEN").unwrap_or_default();
+    if expected.is_empty() {
+        return Err(ApiError::Unauthorized("Write token is not configured.".to_string()));
+    }
+
+    let provided = req
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .unwrap_or("");
+
+    if provided == expected {
+        Ok(())
+    } else {
+        Err(ApiError::Unauthorized("Invalid or missing bearer token.".to_string()))
+    }
+}
+
+/// Core request dispatch. Each branch uses `?` so a parse or upstream failure
+/// maps straight to the matching status instead of being swallowed as a 200.
+async fn dispatch(req: Request) -> Result<Response<Body>, ApiError> {
+    // Reads are public; every mutating method must present the write token.
+    authorize(&req)?;
+
+    let supabase_url = env::var("SUPABASE_URL").unwrap_or_default();
+    let supabase_key = env::var("SUPABASE_ANON_KEY").unwrap_or_default();
+
+    if supabase_url.is_empty() || supabase_key.is_empty() {
+        return Err(ApiError::Internal(
+            "Backend environment variables are not set".to_string(),
+        ));
+    }
+
+    let query_parts = parse_query(&req.uri().to_string());
 
-    let client = reqwest::Client::new();
+    let client = http_client();
 
     match *req.method() {
         Method::GET => {
             let search_term = query_parts.get("query").cloned().unwrap_or_default();
-            let page: usize = query_parts.get("page").and_then(|p| p.parse().ok()).unwrap_or(0);
-
             let items_per_page = 8;
-            let from = page * items_per_page;
-            let to = from + items_per_page - 1;
 
             let mut target_url = format!("{}/rest/v1/movies?select=*", supabase_url);
             if !search_term.is_empty() {
                 target_url.push_str(&format!("&title=ilike.*{}*", search_term));
             }
-            target_url.push_str("&order=release_date.desc");
+            target_url.push_str("&order=release_date.desc.nullslast,id.desc");
 
-            let res = client
-                .get(target_url)
+            // Keyset pagination: an opaque `cursor` walks forward in O(1)
+            // regardless of depth. The legacy `page` param still drives offset
+            // pagination via the `Range` header for one more release.
+            let cursor = query_parts
+                .get("cursor")
+                .filter(|c| !c.is_empty())
+                .and_then(|c| decode_cursor(c));
+
+            let mut request = client
+                .get(&target_url)
                 .header("apikey", &supabase_key)
-                .header("Authorization", format!("Bearer {}", supabase_key))
-                .header("Range", format!("{}-{}", from, to))
-                .header("Prefer", "count=exact")
-                .send()
-                .await?;
+                .header("Authorization", format!("Bearer {}", supabase_key));
+
+            if let Some((release_date, id)) = &cursor {
+                // A single OR predicate expresses the lexicographic "(date, id)
+                // is strictly before the cursor" comparison the ordering needs.
+                // Two separate ANDed filters would drop every row sharing the
+                // cursor's date and any earlier-date/higher-id row.
+                request = request.query(&[
+                    ("or", keyset_predicate(release_date, id)),
+                    ("limit", items_per_page.to_string()),
+                ]);
+            } else {
+                let page: usize =
+                    query_parts.get("page").and_then(|p| p.parse().ok()).unwrap_or(0);
+                let from = page * items_per_page;
+                let to = from + items_per_page - 1;
+                request = request
+                    .header("Range", format!("{}-{}", from, to))
+                    .header("Prefer", "count=exact");
+            }
+
+            let res = send_with_retry(request).await?;
 
             let total_count = res
                 .headers()
                 .get("content-range")
                 .and_then(|v| v.to_str().ok())
-                .and_then(|v| v.split('/').last())
+                .and_then(|v| v.split('/').next_back())
                 .map(|v| v.to_string())
                 .unwrap_or_else(|| "0".to_string());
 
             let movies: Vec<Movie> = res.json().await?;
 
-            Ok(json!({
-                "movies": movies,
-                "total": total_count.parse::<usize>().unwrap_or(0)
-            }))
+            // A full page means there may be more rows; fewer means the end.
+            let next_cursor = if movies.len() < items_per_page {
+                None
+            } else {
+                movies
+                    .last()
+                    .and_then(cursor_fields)
+                    .map(|(date, id)| encode_cursor(&date, &id))
+            };
+
+            Ok(json_response(
+                StatusCode::OK,
+                json!({
+                    "movies": movies,
+                    "total": total_count.parse::<usize>().unwrap_or(0),
+                    "next_cursor": next_cursor
+                }),
+            ))
+        }
+        Method::POST if query_parts.get("batch").map(|b| b == "1").unwrap_or(false) => {
+            let body = req.into_body();
+            let payload: BatchRequest = serde_json::from_slice(body.as_ref())?;
+            handle_batch(client, &supabase_url, &supabase_key, payload).await
         }
         Method::POST => {
-            let body_bytes = to_bytes(req.into_body()).await?;
-            let payload: MovieInput = match serde_json::from_slice(&body_bytes) {
-                Ok(data) => data,
-                Err(_) => {
-                    return Ok(json!({
-                        "error": "Invalid JSON payload."
-                    }));
-                }
-            };
+            let body = req.into_body();
+            let mut payload: MovieInput = serde_json::from_slice(body.as_ref())?;
+
+            // Opt-in: fill in any missing fields from the metadata provider
+            // before persisting. Best-effort — a provider hiccup leaves the
+            // user-supplied values untouched.
+            if query_parts.get("enrich").map(|e| e == "1").unwrap_or(false) {
+                enrich_movie(client, &mut payload).await;
+            }
 
             let target_url = format!("{}/rest/v1/movies", supabase_url);
-            let res = client
-                .post(target_url)
-                .header("apikey", &supabase_key)
-                .header("Authorization", format!("Bearer {}", supabase_key))
-                .header("Prefer", "return=representation")
-                .json(&payload)
-                .send()
-                .await?;
+            let res = send_with_retry(
+                client
+                    .post(target_url)
+                    .header("apikey", &supabase_key)
+                    .header("Authorization", format!("Bearer {}", supabase_key))
+                    .header("Prefer", "return=representation")
+                    .json(&payload),
+            )
+            .await?;
 
-            if !res.status().is_success() {
+            let status = res.status();
+            if !status.is_success() {
                 let details = res.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-                return Ok(json!({
-                    "error": "Supabase insert failed.",
-                    "details": details
-                }));
+                return Err(ApiError::Upstream(
+                    StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY),
+                    details,
+                ));
             }
 
-            let created: Vec<Movie> = res.json().await.unwrap_or_default();
-            Ok(json!({
-                "movie": created.into_iter().next()
-            }))
+            let created: Vec<Movie> = res.json().await?;
+            Ok(json_response(
+                StatusCode::OK,
+                json!({ "movie": created.into_iter().next() }),
+            ))
         }
         Method::PATCH => {
             let id = match query_parts.get("id") {
                 Some(value) if !value.is_empty() => value,
-                _ => {
-                    return Ok(json!({
-                        "error": "Missing id query parameter."
-                    }));
-                }
+                _ => return Err(ApiError::BadRequest("Missing id query parameter.".to_string())),
             };
 
-            let body_bytes = to_bytes(req.into_body()).await?;
-            let payload: MovieUpdate = match serde_json::from_slice(&body_bytes) {
-                Ok(data) => data,
-                Err(_) => {
-                    return Ok(json!({
-                        "error": "Invalid JSON payload."
-                    }));
-                }
-            };
+            let body = req.into_body();
+            let payload: MovieUpdate = serde_json::from_slice(body.as_ref())?;
 
             if payload.title.is_none()
                 && payload.tagline.is_none()
                 && payload.popularity.is_none()
                 && payload.release_date.is_none()
             {
-                return Ok(json!({
-                    "error": "No fields provided for update."
-                }));
+                return Err(ApiError::BadRequest("No fields provided for update.".to_string()));
             }
 
             let target_url = format!("{}/rest/v1/movies?id=eq.{}", supabase_url, id);
-            let res = client
-                .patch(target_url)
-                .header("apikey", &supabase_key)
-                .header("Authorization", format!("Bearer {}", supabase_key))
-                .header("Prefer", "return=representation")
-                .json(&payload)
-                .send()
-                .await?;
+            let res = send_with_retry(
+                client
+                    .patch(target_url)
+                    .header("apikey", &supabase_key)
+                    .header("Authorization", format!("Bearer {}", supabase_key))
+                    .header("Prefer", "return=representation")
+                    .json(&payload),
+            )
+            .await?;
 
-            if !res.status().is_success() {
+            let status = res.status();
+            if !status.is_success() {
                 let details = res.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-                return Ok(json!({
-                    "error": "Supabase update failed.",
-                    "details": details
-                }));
+                return Err(ApiError::Upstream(
+                    StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY),
+                    details,
+                ));
             }
 
-            let updated: Vec<Movie> = res.json().await.unwrap_or_default();
-            Ok(json!({
-                "movie": updated.into_iter().next()
-            }))
+            let updated: Vec<Movie> = res.json().await?;
+            Ok(json_response(
+                StatusCode::OK,
+                json!({ "movie": updated.into_iter().next() }),
+            ))
         }
         Method::DELETE => {
             let id = match query_parts.get("id") {
                 Some(value) if !value.is_empty() => value,
-                _ => {
-                    return Ok(json!({
-                        "error": "Missing id query parameter."
-                    }));
-                }
+                _ => return Err(ApiError::BadRequest("Missing id query parameter.".to_string())),
             };
 
             let target_url = format!("{}/rest/v1/movies?id=eq.{}", supabase_url, id);
-            let res = client
-                .delete(target_url)
-                .header("apikey", &supabase_key)
-                .header("Authorization", format!("Bearer {}", supabase_key))
-                .send()
-                .await?;
+            let res = send_with_retry(
+                client
+                    .delete(target_url)
+                    .header("apikey", &supabase_key)
+                    .header("Authorization", format!("Bearer {}", supabase_key)),
+            )
+            .await?;
 
-            if !res.status().is_success() {
+            let status = res.status();
+            if !status.is_success() {
                 let details = res.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-                return Ok(json!({
-                    "error": "Supabase delete failed.",
-                    "details": details
-                }));
+                return Err(ApiError::Upstream(
+                    StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY),
+                    details,
+                ));
+            }
+
+            Ok(json_response(StatusCode::OK, json!({ "status": "deleted" })))
+        }
+        _ => Err(ApiError::BadRequest("Unsupported method.".to_string())),
+    }
+}
+
+/// Fill the empty `tagline`/`popularity`/`release_date` fields of a new movie
+/// from a TMDB-style metadata provider (`METADATA_API_URL` + `METADATA_API_KEY`).
+/// Any missing config, unreachable provider, or empty result set leaves the
+/// payload exactly as the user sent it.
+async fn enrich_movie(client: &reqwest::Client, payload: &mut MovieInput) {
+    // Nothing missing means nothing to do.
+    if payload.tagline.is_some() && payload.popularity.is_some() && payload.release_date.is_some() {
+        return;
+    }
+
+    let base_url = match env::var("METADATA_API_URL") {
+        Ok(url) if !url.is_empty() => url,
+        _ => return,
+    };
+    let api_key = env::var("METADATA_API_KEY").unwrap_or_default();
+
+    let res = send_with_retry(
+        client
+            .get(format!("{}/search/movie", base_url.trim_end_matches('/')))
+            .query(&[("query", payload.title.as_str()), ("api_key", api_key.as_str())]),
+    )
+    .await;
+
+    let body: Value = match res {
+        Ok(res) if res.status().is_success() => match res.json().await {
+            Ok(body) => body,
+            Err(_) => return,
+        },
+        _ => return,
+    };
+
+    // TMDB wraps hits in a `results` array; take the top match.
+    let hit = match body.get("results").and_then(|r| r.as_array()).and_then(|r| r.first()) {
+        Some(hit) => hit,
+        None => return,
+    };
+
+    if payload.tagline.is_none() {
+        if let Some(overview) = hit.get("overview").and_then(|v| v.as_str()) {
+            if !overview.is_empty() {
+                payload.tagline = Some(overview.to_string());
+            }
+        }
+    }
+    if payload.popularity.is_none() {
+        if let Some(popularity) = hit.get("popularity").and_then(|v| v.as_f64()) {
+            payload.popularity = Some(popularity);
+        }
+    }
+    if payload.release_date.is_none() {
+        if let Some(release_date) = hit.get("release_date").and_then(|v| v.as_str()) {
+            if !release_date.is_empty() {
+                payload.release_date = Some(release_date.to_string());
+            }
+        }
+    }
+}
+
+/// Execute a bulk `insert`/`update`/`delete` request. Each operation runs on
+/// its own so a single failing item is reported in the result array rather than
+/// aborting the whole batch.
+async fn handle_batch(
+    client: &reqwest::Client,
+    supabase_url: &str,
+    supabase_key: &str,
+    payload: BatchRequest,
+) -> Result<Response<Body>, ApiError> {
+    let mut results: Vec<Value> = Vec::new();
+
+    for input in &payload.insert {
+        let target_url = format!("{}/rest/v1/movies", supabase_url);
+        let res = send_with_retry(
+            client
+                .post(target_url)
+                .header("apikey", supabase_key)
+                .header("Authorization", format!("Bearer {}", supabase_key))
+                .header("Prefer", "return=representation")
+                .json(input),
+        )
+        .await;
+        results.push(batch_result(json!({ "op": "insert" }), res).await);
+    }
+
+    for entry in &payload.update {
+        let target_url = format!("{}/rest/v1/movies?id=eq.{}", supabase_url, entry.id);
+        let res = send_with_retry(
+            client
+                .patch(target_url)
+                .header("apikey", supabase_key)
+                .header("Authorization", format!("Bearer {}", supabase_key))
+                .header("Prefer", "return=representation")
+                .json(&entry.fields),
+        )
+        .await;
+        results.push(batch_result(json!({ "op": "update", "id": entry.id }), res).await);
+    }
+
+    for id in &payload.delete {
+        let target_url = format!("{}/rest/v1/movies?id=eq.{}", supabase_url, id);
+        let res = send_with_retry(
+            client
+                .delete(target_url)
+                .header("apikey", supabase_key)
+                .header("Authorization", format!("Bearer {}", supabase_key)),
+        )
+        .await;
+        results.push(batch_result(json!({ "op": "delete", "id": id }), res).await);
+    }
+
+    Ok(json_response(StatusCode::OK, json!({ "results": results })))
+}
+
+/// Fold a single upstream response into a per-item batch result, starting from
+/// a base object that already carries the operation kind (and, for update and
+/// delete, the id).
+async fn batch_result(mut base: Value, res: Result<reqwest::Response, reqwest::Error>) -> Value {
+    let object = base.as_object_mut().expect("base is always a JSON object");
+    match res {
+        Ok(res) if res.status().is_success() => {
+            object.insert("success".to_string(), Value::Bool(true));
+            // A representation body (insert/update) is echoed back; a bodyless
+            // delete just reports success.
+            if let Ok(rows) = res.json::<Vec<Movie>>().await {
+                if let Some(movie) = rows.into_iter().next() {
+                    if let Ok(value) = serde_json::to_value(movie) {
+                        object.insert("movie".to_string(), value);
+                    }
+                }
             }
+        }
+        Ok(res) => {
+            let status = res.status().as_u16();
+            let details = res.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            object.insert("success".to_string(), Value::Bool(false));
+            object.insert("error".to_string(), json!(format!("{}: {}", status, details)));
+        }
+        Err(err) => {
+            object.insert("success".to_string(), Value::Bool(false));
+            object.insert("error".to_string(), json!(err.to_string()));
+        }
+    }
+    base
+}
 
-            Ok(json!({
-                "status": "deleted"
-            }))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_with_auth(method: Method, auth: Option<&str>) -> Request {
+        let mut builder = http::Request::builder().method(method).uri("https://example.test/");
+        if let Some(value) = auth {
+            builder = builder.header("Authorization", value);
         }
-        _ => Ok(json!({
-            "error": "Unsupported method."
-        })),
+        builder.body(Body::Empty).unwrap()
+    }
+
+    #[test]
+    fn authorize_lets_get_through_without_a_token() {
+        let req = request_with_auth(Method::GET, None);
+        assert!(authorize(&req).is_ok());
+    }
+
+    #[test]
+    fn authorize_gates_writes_on_the_bearer_token() {
+        // Single test so the shared env var isn't raced by parallel cases.
+        env::set_var("API_WRITE_TOKEN", "s3cret");
+        assert!(authorize(&request_with_auth(Method::POST, Some("Bearer s3cret"))).is_ok());
+        assert!(matches!(
+            authorize(&request_with_auth(Method::POST, Some("Bearer nope"))),
+            Err(ApiError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            authorize(&request_with_auth(Method::DELETE, None)),
+            Err(ApiError::Unauthorized(_))
+        ));
+        env::remove_var("API_WRITE_TOKEN");
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let encoded = encode_cursor("2021-05-01", "42");
+        assert_eq!(decode_cursor(&encoded), Some(("2021-05-01".to_string(), "42".to_string())));
+    }
+
+    #[test]
+    fn cursor_survives_query_string_parsing() {
+        // The real HTTP path runs the cursor through `parse_query`; the value
+        // must come back unchanged or the round-trip silently resets to page 1.
+        let encoded = encode_cursor("2021-05-01", "42");
+        let parts = parse_query(&format!("/api/movies?page=0&cursor={}", encoded));
+        assert_eq!(parts.get("cursor").map(String::as_str), Some(encoded.as_str()));
+        assert_eq!(
+            decode_cursor(parts.get("cursor").unwrap()),
+            Some(("2021-05-01".to_string(), "42".to_string()))
+        );
+    }
+
+    #[test]
+    fn decode_cursor_rejects_garbage() {
+        assert_eq!(decode_cursor("not base64!!"), None);
+    }
+
+    #[test]
+    fn backoff_grows_exponentially() {
+        // Base doubles each attempt; jitter stays under 100ms.
+        assert!(backoff_delay(0) >= Duration::from_millis(100));
+        assert!(backoff_delay(0) < Duration::from_millis(200));
+        assert!(backoff_delay(1) >= Duration::from_millis(200));
+        assert!(backoff_delay(2) >= Duration::from_millis(400));
+    }
+
+    #[test]
+    fn keyset_predicate_covers_the_tie_break_boundary() {
+        // Must keep rows on an earlier date OR same date with an earlier id, plus
+        // the nulls-last tail, so no row sharing the cursor's date is dropped.
+        assert_eq!(
+            keyset_predicate("2021-05-01", "42"),
+            "(release_date.lt.2021-05-01,and(release_date.eq.2021-05-01,id.lt.42),release_date.is.null)"
+        );
+    }
+
+    #[test]
+    fn keyset_predicate_walks_the_null_date_tail() {
+        // An empty date means we're already inside the null-date tail and should
+        // continue by id instead of restarting at the first null row.
+        assert_eq!(keyset_predicate("", "42"), "(and(release_date.is.null,id.lt.42))");
+    }
+
+    #[test]
+    fn cursor_fields_encodes_a_null_release_date() {
+        // A full page ending on a null-date row must still yield a cursor.
+        let movie = Movie {
+            id: 42,
+            title: "Untitled".to_string(),
+            tagline: None,
+            popularity: None,
+            release_date: None,
+        };
+        assert_eq!(cursor_fields(&movie), Some((String::new(), "42".to_string())));
     }
 }
@@ -0,0 +1,14 @@
+fn main() {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    let enc = STANDARD.encode("2021-05-01|42");
+    println!("encoded: {:?}", enc);
+    // a naive query split on every '=' truncates a padded value
+    // (`parse_query` uses `splitn(2, '=')`, and cursors are unpadded URL-safe)
+    let s = format!("cursor={}", enc);
+    let mut parts = s.split('=');
+    let _k = parts.next();
+    let v = parts.next().unwrap_or("");
+    println!("value after naive split: {:?}", v);
+    println!("decode full: {:?}", STANDARD.decode(&enc).is_ok());
+    println!("decode truncated: {:?}", STANDARD.decode(v).is_ok());
+}
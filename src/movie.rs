@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+/// A movie row as stored in Supabase and returned to API clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Movie {
+    pub id: i64,
+    pub title: String,
+    pub tagline: Option<String>,
+    pub popularity: Option<f64>,
+    pub release_date: Option<String>,
+}